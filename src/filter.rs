@@ -1,21 +1,52 @@
-use std::str::FromStr;
+use std::{borrow::Cow, str::FromStr};
 
-use mpd_client::responses::Song;
+use mpd_client::{responses::Song, tag::Tag};
+use regex::Regex;
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum FilterField {
     Title,
     Artist,
     Album,
+    Genre,
+    /// The year component of the `Date` tag.
+    Year,
+    Track,
+    /// The song's URI, relative to the music directory root.
+    File,
+    /// Title, artist, or album.
     Any,
 }
 
 #[derive(Debug)]
 pub enum FilterError {
-    InvalidField,
+    InvalidField(String),
     InvalidValue,
+    InvalidNumber(String),
+    InvalidRegex(regex::Error),
+    UnbalancedParens,
+    UnexpectedToken(String),
+    UnexpectedEnd,
+    EmptyExpression,
 }
 
+impl std::fmt::Display for FilterError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FilterError::InvalidField(field) => write!(f, "unknown filter field \"{field}\""),
+            FilterError::InvalidValue => write!(f, "missing or invalid filter value"),
+            FilterError::InvalidNumber(value) => write!(f, "\"{value}\" is not a valid number"),
+            FilterError::InvalidRegex(err) => write!(f, "invalid regex: {err}"),
+            FilterError::UnbalancedParens => write!(f, "unbalanced parentheses"),
+            FilterError::UnexpectedToken(token) => write!(f, "unexpected token \"{token}\""),
+            FilterError::UnexpectedEnd => write!(f, "unexpected end of filter expression"),
+            FilterError::EmptyExpression => write!(f, "empty filter expression"),
+        }
+    }
+}
+
+impl std::error::Error for FilterError {}
+
 impl FromStr for FilterField {
     type Err = FilterError;
 
@@ -24,89 +55,676 @@ impl FromStr for FilterField {
             "title" => Ok(FilterField::Title),
             "artist" => Ok(FilterField::Artist),
             "album" => Ok(FilterField::Album),
+            "genre" => Ok(FilterField::Genre),
+            "date" | "year" => Ok(FilterField::Year),
+            "track" => Ok(FilterField::Track),
+            "file" => Ok(FilterField::File),
             "any" => Ok(FilterField::Any),
-            _ => Err(FilterError::InvalidField),
+            other => Err(FilterError::InvalidField(other.to_string())),
         }
     }
 }
 
+impl FilterField {
+    fn is_numeric(self) -> bool {
+        matches!(self, FilterField::Year | FilterField::Track)
+    }
+
+    /// The MPD tag name to use when building a native filter expression for this field, if any.
+    ///
+    /// `Any` has no single tag; it is expanded into an `OR` group by the caller instead.
+    fn mpd_tag(self) -> Option<&'static str> {
+        match self {
+            FilterField::Title => Some("Title"),
+            FilterField::Artist => Some("Artist"),
+            FilterField::Album => Some("Album"),
+            FilterField::Genre => Some("Genre"),
+            FilterField::Year => Some("Date"),
+            FilterField::Track => Some("Track"),
+            FilterField::File => Some("file"),
+            FilterField::Any => None,
+        }
+    }
+
+    /// The raw string value(s) of this field on a song, if present. Empty for `Any`, which is
+    /// expanded by the caller instead.
+    fn string_values<'s>(self, song: &'s Song) -> Vec<Cow<'s, str>> {
+        match self {
+            FilterField::Title => song.title().map(Cow::Borrowed).into_iter().collect(),
+            FilterField::Artist => song.artists().iter().map(|a| Cow::Borrowed(a.as_str())).collect(),
+            FilterField::Album => song.album().map(Cow::Borrowed).into_iter().collect(),
+            FilterField::Genre => song
+                .tags
+                .get(&Tag::Genre)
+                .into_iter()
+                .flatten()
+                .map(|v| Cow::Borrowed(v.as_str()))
+                .collect(),
+            FilterField::Year => song
+                .tags
+                .get(&Tag::Date)
+                .into_iter()
+                .flatten()
+                .map(|v| Cow::Borrowed(v.as_str()))
+                .collect(),
+            FilterField::Track => song
+                .tags
+                .get(&Tag::Track)
+                .into_iter()
+                .flatten()
+                .map(|v| Cow::Borrowed(v.as_str()))
+                .collect(),
+            FilterField::File => vec![Cow::Borrowed(song.url.as_str())],
+            FilterField::Any => Vec::new(),
+        }
+    }
+
+    /// The numeric value of this field on a song, if present and parseable.
+    ///
+    /// For `Year`, this takes the leading digits of the `Date` tag (which may be a full date like
+    /// `1987-03-01`). For `Track`, this takes the part before a `/` (MPD sometimes stores
+    /// `track/total`).
+    fn numeric_value(self, song: &Song) -> Option<i64> {
+        let raw = self.string_values(song).into_iter().next()?;
+
+        let digits: String = match self {
+            FilterField::Year => raw.chars().take_while(|c| c.is_ascii_digit()).collect(),
+            FilterField::Track => raw.split('/').next().unwrap_or(&raw).to_string(),
+            _ => raw.to_string(),
+        };
+
+        digits.parse().ok()
+    }
+}
+
+/// Escape a value for inclusion in a single-quoted MPD filter expression string.
+///
+/// See <https://mpd.readthedocs.io/en/latest/protocol.html#filters>: `\`, `'` and `"` all need to
+/// be backslash-escaped.
+fn escape_value(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+
+    for c in value.chars() {
+        if matches!(c, '\\' | '\'' | '"') {
+            escaped.push('\\');
+        }
+
+        escaped.push(c);
+    }
+
+    escaped
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum NumCmp {
+    Eq,
+    Gt,
+    Ge,
+    Lt,
+    Le,
+}
+
 #[derive(Debug, Clone)]
-pub struct Filter {
-    field: FilterField,
-    value: String,
-    invert: bool,
+enum LeafOp {
+    Contains { value: String, case_sensitive: bool },
+    Exact { value: String, case_sensitive: bool },
+    Regex {
+        regex: Regex,
+        source: String,
+        case_sensitive: bool,
+    },
+    NumCmp { cmp: NumCmp, value: i64 },
+    NumRange { lo: i64, hi: i64 },
 }
 
-impl FromStr for Filter {
-    type Err = FilterError;
+#[derive(Debug, Clone)]
+struct Leaf {
+    field: FilterField,
+    op: LeafOp,
+}
 
-    fn from_str(mut s: &str) -> Result<Self, Self::Err> {
-        if !s.contains(':') {
-            let mut invert = false;
+impl Leaf {
+    fn matches(&self, song: &Song) -> bool {
+        if self.field == FilterField::Any {
+            return [FilterField::Title, FilterField::Artist, FilterField::Album]
+                .into_iter()
+                .any(|field| {
+                    Leaf {
+                        field,
+                        op: self.op.clone(),
+                    }
+                    .matches(song)
+                });
+        }
 
-            if s.starts_with('!') {
-                s = &s[1..];
-                invert = true;
+        match &self.op {
+            LeafOp::Contains {
+                value,
+                case_sensitive,
+            } => self.field.string_values(song).iter().any(|actual| {
+                if *case_sensitive {
+                    actual.contains(value.as_str())
+                } else {
+                    actual.to_lowercase().contains(&value.to_lowercase())
+                }
+            }),
+            LeafOp::Exact {
+                value,
+                case_sensitive,
+            } => self.field.string_values(song).iter().any(|actual| {
+                if *case_sensitive {
+                    actual.as_ref() == value.as_str()
+                } else {
+                    actual.eq_ignore_ascii_case(value)
+                }
+            }),
+            LeafOp::Regex { regex, .. } => self
+                .field
+                .string_values(song)
+                .iter()
+                .any(|actual| regex.is_match(actual)),
+            LeafOp::NumCmp { cmp, value } => {
+                self.field.numeric_value(song).is_some_and(|actual| match cmp {
+                    NumCmp::Eq => actual == *value,
+                    NumCmp::Gt => actual > *value,
+                    NumCmp::Ge => actual >= *value,
+                    NumCmp::Lt => actual < *value,
+                    NumCmp::Le => actual <= *value,
+                })
             }
+            LeafOp::NumRange { lo, hi } => self
+                .field
+                .numeric_value(song)
+                .is_some_and(|actual| actual >= *lo && actual <= *hi),
+        }
+    }
+
+    /// Compile this leaf into a native MPD filter expression clause, if possible.
+    ///
+    /// Case-insensitive `contains`/`==` and case-*sensitive* `=~` clauses map onto the MPD filter
+    /// grammar (MPD's `=~` operator has no case-insensitivity flag, unlike our default `=~`, which
+    /// is compiled with `(?i)` client-side); numeric comparisons, ranges, case-sensitive
+    /// `contains`/`==`, and case-insensitive regex have no native equivalent and fall back to
+    /// [`Leaf::matches`].
+    fn to_mpd_expr(&self) -> Option<String> {
+        if self.field == FilterField::Any {
+            let group = [FilterField::Title, FilterField::Artist, FilterField::Album]
+                .into_iter()
+                .map(|field| {
+                    Leaf {
+                        field,
+                        op: self.op.clone(),
+                    }
+                    .to_mpd_expr()
+                })
+                .collect::<Option<Vec<_>>>()?
+                .join(" OR ");
 
-            return Ok(Filter {
-                field: FilterField::Any,
-                value: s.to_string(),
-                invert,
-            });
+            return Some(format!("({group})"));
         }
 
-        let mut parts = s.splitn(2, ':');
-        let mut field = parts.next().ok_or(FilterError::InvalidField)?;
-        let value = parts.next().ok_or(FilterError::InvalidValue)?;
+        let tag = self.field.mpd_tag()?;
 
-        let mut inverted = false;
-        if field.starts_with('!') {
-            inverted = true;
-            field = &field[1..];
+        match &self.op {
+            LeafOp::Contains {
+                value,
+                case_sensitive: false,
+            } => Some(format!("({tag} contains '{}')", escape_value(value))),
+            LeafOp::Exact {
+                value,
+                case_sensitive: false,
+            } => Some(format!("({tag} == '{}')", escape_value(value))),
+            LeafOp::Regex {
+                source,
+                regex: _,
+                case_sensitive: true,
+            } => Some(format!("({tag} =~ '{}')", escape_value(source))),
+            // case-sensitive contains/==, case-insensitive regex, numeric comparisons, and ranges
+            // have no native equivalent
+            _ => None,
         }
+    }
+}
 
-        Ok(Filter {
-            field: FilterField::from_str(field)?,
-            value: value.to_string(),
-            invert: inverted,
-        })
+/// An MPD filter expression, as parsed from the `--filter` flag.
+///
+/// Supports grouping with parentheses and boolean combinators, e.g.
+/// `(genre:jazz OR genre:blues) AND !year<1960`.
+#[derive(Debug, Clone)]
+pub enum FilterExpr {
+    And(Vec<FilterExpr>),
+    Or(Vec<FilterExpr>),
+    Not(Box<FilterExpr>),
+    Leaf(Leaf),
+}
+
+impl FilterExpr {
+    pub fn matches(&self, song: &Song) -> bool {
+        match self {
+            FilterExpr::And(exprs) => exprs.iter().all(|e| e.matches(song)),
+            FilterExpr::Or(exprs) => exprs.iter().any(|e| e.matches(song)),
+            FilterExpr::Not(expr) => !expr.matches(song),
+            FilterExpr::Leaf(leaf) => leaf.matches(song),
+        }
+    }
+
+    /// Compile this expression into a native MPD filter expression, if every leaf in it has a
+    /// server-side equivalent.
+    ///
+    /// Returns `None` if any part of the expression can't be expressed server-side, in which case
+    /// the caller should fall back to [`FilterExpr::matches`].
+    pub fn to_mpd_expr(&self) -> Option<String> {
+        match self {
+            FilterExpr::And(exprs) => combine_mpd_exprs(exprs, "AND"),
+            FilterExpr::Or(exprs) => combine_mpd_exprs(exprs, "OR"),
+            FilterExpr::Not(expr) => expr.to_mpd_expr().map(|e| format!("(!{e})")),
+            FilterExpr::Leaf(leaf) => leaf.to_mpd_expr(),
+        }
     }
 }
 
-impl Filter {
-    pub fn is_inverted(&self) -> bool {
-        self.invert
+fn combine_mpd_exprs(exprs: &[FilterExpr], op: &str) -> Option<String> {
+    let parts = exprs
+        .iter()
+        .map(FilterExpr::to_mpd_expr)
+        .collect::<Option<Vec<_>>>()?;
+
+    Some(format!("({})", parts.join(&format!(" {op} "))))
+}
+
+/// Compile a list of top-level `--filter` expressions into a single native MPD filter expression.
+///
+/// Multiple `--filter` flags are ORed together (a song is kept if it matches any of them), so the
+/// combined expression is an `OR` group. Returns `None` if there are no filters, or if any of them
+/// has no server-side equivalent, in which case the caller should fall back to client-side
+/// filtering via [`FilterExpr::matches`].
+pub fn compile_mpd_expr(filters: &[FilterExpr]) -> Option<String> {
+    match filters {
+        [] => None,
+        [single] => single.to_mpd_expr(),
+        many => combine_mpd_exprs(many, "OR"),
     }
+}
 
-    pub fn matches(&self, song: &Song) -> bool {
-        let to_compare = match self.field {
-            FilterField::Title => song.title(),
-            FilterField::Artist => {
-                let artists = song.artists();
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    LParen,
+    RParen,
+    And,
+    Or,
+    Not,
+    Leaf(String),
+}
 
-                if artists.is_empty() {
-                    None
-                } else {
-                    Some(artists[0].as_str())
+fn tokenize(input: &str) -> Result<Vec<Token>, FilterError> {
+    let mut tokens = Vec::new();
+    let mut chars = input.chars().peekable();
+
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+            continue;
+        }
+
+        match c {
+            '(' => {
+                tokens.push(Token::LParen);
+                chars.next();
+            }
+            ')' => {
+                tokens.push(Token::RParen);
+                chars.next();
+            }
+            _ => {
+                let mut buf = String::new();
+                let mut in_quotes = false;
+
+                while let Some(&c) = chars.peek() {
+                    if c == '"' {
+                        in_quotes = !in_quotes;
+                        chars.next();
+                        continue;
+                    }
+
+                    if !in_quotes && (c.is_whitespace() || c == '(' || c == ')') {
+                        break;
+                    }
+
+                    buf.push(c);
+                    chars.next();
                 }
+
+                tokens.push(match buf.to_ascii_lowercase().as_str() {
+                    "and" => Token::And,
+                    "or" => Token::Or,
+                    "not" | "!" => Token::Not,
+                    _ => Token::Leaf(buf),
+                });
             }
-            FilterField::Album => song.album(),
-            FilterField::Any => {
-                return self.as_field(FilterField::Title).matches(song)
-                    || self.as_field(FilterField::Artist).matches(song)
-                    || self.as_field(FilterField::Album).matches(song)
+        }
+    }
+
+    Ok(tokens)
+}
+
+/// Split a leaf token into its field name and the remainder (operator + value), at the first
+/// character that can't be part of a field identifier.
+fn split_field(raw: &str) -> Option<(&str, &str)> {
+    let idx = raw.find(|c: char| !(c.is_alphanumeric() || c == '_'))?;
+    Some((&raw[..idx], &raw[idx..]))
+}
+
+const NUMERIC_OPS: &[(&str, NumCmp)] = &[
+    (">=", NumCmp::Ge),
+    ("<=", NumCmp::Le),
+    (">", NumCmp::Gt),
+    ("<", NumCmp::Lt),
+];
+
+fn parse_leaf(raw: &str) -> Result<FilterExpr, FilterError> {
+    if let Some(rest) = raw.strip_prefix('!') {
+        return Ok(FilterExpr::Not(Box::new(parse_leaf(rest)?)));
+    }
+
+    let (field_str, rest) = split_field(raw).ok_or(FilterError::InvalidValue)?;
+    let field = FilterField::from_str(field_str)?;
+
+    // numeric comparisons (year>=1990) only apply to numeric fields
+    if field.is_numeric() {
+        for (op, cmp) in NUMERIC_OPS {
+            if let Some(value) = rest.strip_prefix(op) {
+                let value = value
+                    .parse()
+                    .map_err(|_| FilterError::InvalidNumber(value.to_string()))?;
+                return Ok(FilterExpr::Leaf(Leaf {
+                    field,
+                    op: LeafOp::NumCmp { cmp: *cmp, value },
+                }));
             }
-        };
+        }
+    }
 
-        to_compare.is_some_and(|s| s.to_lowercase().contains(&self.value))
+    if let Some(value) = rest.strip_prefix("===") {
+        return Ok(FilterExpr::Leaf(Leaf {
+            field,
+            op: LeafOp::Exact {
+                value: value.to_string(),
+                case_sensitive: true,
+            },
+        }));
+    }
+
+    if let Some(value) = rest.strip_prefix("==") {
+        return Ok(FilterExpr::Leaf(Leaf {
+            field,
+            op: LeafOp::Exact {
+                value: value.to_string(),
+                case_sensitive: false,
+            },
+        }));
     }
 
-    pub fn as_field(&self, field: FilterField) -> Self {
-        Self {
+    if let Some(source) = rest.strip_prefix("=~~") {
+        let regex = Regex::new(source).map_err(FilterError::InvalidRegex)?;
+        return Ok(FilterExpr::Leaf(Leaf {
             field,
-            value: self.value.clone(),
-            invert: self.invert,
+            op: LeafOp::Regex {
+                regex,
+                source: source.to_string(),
+                case_sensitive: true,
+            },
+        }));
+    }
+
+    if let Some(source) = rest.strip_prefix("=~") {
+        let regex =
+            Regex::new(&format!("(?i){source}")).map_err(FilterError::InvalidRegex)?;
+        return Ok(FilterExpr::Leaf(Leaf {
+            field,
+            op: LeafOp::Regex {
+                regex,
+                source: source.to_string(),
+                case_sensitive: false,
+            },
+        }));
+    }
+
+    if let Some(value) = rest.strip_prefix("::") {
+        return Ok(FilterExpr::Leaf(Leaf {
+            field,
+            op: LeafOp::Contains {
+                value: value.to_string(),
+                case_sensitive: true,
+            },
+        }));
+    }
+
+    if let Some(value) = rest.strip_prefix(':') {
+        if field.is_numeric() {
+            if let Some((lo, hi)) = value.split_once("..") {
+                let lo = lo
+                    .parse()
+                    .map_err(|_| FilterError::InvalidNumber(lo.to_string()))?;
+                let hi = hi
+                    .parse()
+                    .map_err(|_| FilterError::InvalidNumber(hi.to_string()))?;
+                return Ok(FilterExpr::Leaf(Leaf {
+                    field,
+                    op: LeafOp::NumRange { lo, hi },
+                }));
+            }
+
+            let value = value
+                .parse()
+                .map_err(|_| FilterError::InvalidNumber(value.to_string()))?;
+            return Ok(FilterExpr::Leaf(Leaf {
+                field,
+                op: LeafOp::NumCmp {
+                    cmp: NumCmp::Eq,
+                    value,
+                },
+            }));
+        }
+
+        return Ok(FilterExpr::Leaf(Leaf {
+            field,
+            op: LeafOp::Contains {
+                value: value.to_string(),
+                case_sensitive: false,
+            },
+        }));
+    }
+
+    Err(FilterError::InvalidValue)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    /// `OR` has the lowest precedence.
+    fn parse_or(&mut self) -> Result<FilterExpr, FilterError> {
+        let mut exprs = vec![self.parse_and()?];
+
+        while matches!(self.peek(), Some(Token::Or)) {
+            self.next();
+            exprs.push(self.parse_and()?);
+        }
+
+        Ok(if exprs.len() == 1 {
+            exprs.remove(0)
+        } else {
+            FilterExpr::Or(exprs)
+        })
+    }
+
+    /// `AND` binds tighter than `OR`.
+    fn parse_and(&mut self) -> Result<FilterExpr, FilterError> {
+        let mut exprs = vec![self.parse_unary()?];
+
+        while matches!(self.peek(), Some(Token::And)) {
+            self.next();
+            exprs.push(self.parse_unary()?);
+        }
+
+        Ok(if exprs.len() == 1 {
+            exprs.remove(0)
+        } else {
+            FilterExpr::And(exprs)
+        })
+    }
+
+    /// `NOT` binds tighter than `AND`/`OR`.
+    fn parse_unary(&mut self) -> Result<FilterExpr, FilterError> {
+        if matches!(self.peek(), Some(Token::Not)) {
+            self.next();
+            return Ok(FilterExpr::Not(Box::new(self.parse_unary()?)));
+        }
+
+        self.parse_atom()
+    }
+
+    fn parse_atom(&mut self) -> Result<FilterExpr, FilterError> {
+        match self.next().ok_or(FilterError::UnexpectedEnd)? {
+            Token::LParen => {
+                let expr = self.parse_or()?;
+                match self.next() {
+                    Some(Token::RParen) => Ok(expr),
+                    _ => Err(FilterError::UnbalancedParens),
+                }
+            }
+            Token::Leaf(raw) => parse_leaf(&raw),
+            other => Err(FilterError::UnexpectedToken(format!("{other:?}"))),
         }
     }
 }
+
+impl FromStr for FilterExpr {
+    type Err = FilterError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let tokens = tokenize(s)?;
+
+        if tokens.is_empty() {
+            return Err(FilterError::EmptyExpression);
+        }
+
+        let mut parser = Parser { tokens, pos: 0 };
+        let expr = parser.parse_or()?;
+
+        if parser.pos != parser.tokens.len() {
+            return Err(FilterError::UnbalancedParens);
+        }
+
+        Ok(expr)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_substring_and_exact_match() {
+        let expr: FilterExpr = "title:foo".parse().unwrap();
+        assert_eq!(expr.to_mpd_expr().unwrap(), "(Title contains 'foo')");
+
+        let expr: FilterExpr = "artist==Radiohead".parse().unwrap();
+        assert_eq!(expr.to_mpd_expr().unwrap(), "(Artist == 'Radiohead')");
+    }
+
+    #[test]
+    fn case_sensitive_contains_has_no_native_equivalent() {
+        let expr: FilterExpr = "title::foo".parse().unwrap();
+        assert_eq!(expr.to_mpd_expr(), None);
+    }
+
+    #[test]
+    fn case_sensitive_regex_compiles_server_side_but_default_does_not() {
+        let expr: FilterExpr = "title=~~Paranoid".parse().unwrap();
+        assert_eq!(expr.to_mpd_expr().unwrap(), "(Title =~ 'Paranoid')");
+
+        let expr: FilterExpr = "title=~Paranoid".parse().unwrap();
+        assert_eq!(
+            expr.to_mpd_expr(),
+            None,
+            "MPD's =~ has no case-insensitivity flag, so the default case-insensitive \
+             regex must fall back to client-side matching"
+        );
+    }
+
+    #[test]
+    fn parses_numeric_comparison_and_range() {
+        let expr: FilterExpr = "year>=1990".parse().unwrap();
+        assert!(matches!(
+            expr,
+            FilterExpr::Leaf(Leaf {
+                op: LeafOp::NumCmp {
+                    cmp: NumCmp::Ge,
+                    value: 1990
+                },
+                ..
+            })
+        ));
+
+        let expr: FilterExpr = "year:1980..1989".parse().unwrap();
+        assert!(matches!(
+            expr,
+            FilterExpr::Leaf(Leaf {
+                op: LeafOp::NumRange { lo: 1980, hi: 1989 },
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn parses_boolean_grouping_with_negation() {
+        let expr: FilterExpr = "(genre:jazz OR genre:blues) AND !year<1960".parse().unwrap();
+
+        let FilterExpr::And(parts) = expr else {
+            panic!("expected a top-level AND");
+        };
+        assert_eq!(parts.len(), 2);
+        assert!(matches!(parts[0], FilterExpr::Or(_)));
+        assert!(matches!(parts[1], FilterExpr::Not(_)));
+    }
+
+    #[test]
+    fn rejects_unbalanced_parens() {
+        assert!(matches!(
+            "(title:foo".parse::<FilterExpr>(),
+            Err(FilterError::UnbalancedParens)
+        ));
+    }
+
+    #[test]
+    fn compile_mpd_expr_ors_multiple_top_level_filters() {
+        let filters: Vec<FilterExpr> =
+            vec!["title:foo".parse().unwrap(), "artist:bar".parse().unwrap()];
+
+        assert_eq!(
+            compile_mpd_expr(&filters).unwrap(),
+            "((Title contains 'foo') OR (Artist contains 'bar'))"
+        );
+    }
+
+    #[test]
+    fn compile_mpd_expr_none_when_any_leaf_has_no_native_equivalent() {
+        let filters: Vec<FilterExpr> =
+            vec!["title:foo".parse().unwrap(), "year>=1990".parse().unwrap()];
+
+        assert_eq!(compile_mpd_expr(&filters), None);
+    }
+}
@@ -7,15 +7,19 @@
 //! queueing songs as normal and this program will not add anything until the queue is completely
 //! empty and there is nothing left to play.
 
-use std::time::{Duration, Instant};
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
 
 use async_recursion::async_recursion;
 use clap::Parser;
 use color_eyre::eyre::{bail, eyre, Result};
-use filter::Filter;
+use filter::FilterExpr;
 use mpd_client::{
     client::{ConnectionEvent, Subsystem},
     commands::{self as cmd, SongPosition},
+    filter::Filter as MpdFilter,
     responses as res, Client,
 };
 use rand::{rngs::ThreadRng, seq::SliceRandom, thread_rng};
@@ -31,8 +35,16 @@ static GLOBAL: mimalloc::MiMalloc = mimalloc::MiMalloc;
 extern crate tracing;
 
 mod filter;
+#[cfg(feature = "metrics")]
+mod metrics;
 mod state;
 
+#[cfg(feature = "metrics")]
+use std::sync::Arc;
+
+#[cfg(feature = "metrics")]
+use metrics::Metrics;
+
 #[derive(Debug, Parser)]
 #[clap(author, version, about, long_about = None)]
 struct Cli {
@@ -61,20 +73,82 @@ struct Cli {
     #[clap(short, long)]
     pub persist: bool,
 
-    /// Only play songs which contain any of these strings in their titles. Can be specified
-    /// multiple times
+    /// Only play songs matching this filter expression. Can be specified multiple times, in which
+    /// case a song is played if it matches *any* of them.
+    ///
+    /// Supports `field:value` (substring, case-insensitive), `field==value` (exact match),
+    /// `field=~regex` (regex match), numeric comparisons and ranges on `year`/`track`
+    /// (`year>=1990`, `year:1980..1989`), boolean grouping with `AND`/`OR`/`NOT`/`!`, and
+    /// parentheses, e.g. `(genre:jazz OR genre:blues) AND !year<1960`. Fields: `title`, `artist`,
+    /// `album`, `genre`, `date`/`year`, `track`, `file`, `any`. Doubling the operator
+    /// (`::`, `===`, `=~~`) makes the match case-sensitive.
     #[clap(short, long)]
     pub filter: Vec<String>,
+
+    /// Avoid queueing multiple songs by the same artist or from the same album back-to-back
+    ///
+    /// Candidates whose artist or album appeared recently are still eligible, just less likely to
+    /// be picked, so the shuffle stays random while spreading artists/albums across the session.
+    #[clap(long)]
+    pub smart_shuffle: bool,
+
+    /// Push playback metrics to this Prometheus Pushgateway URL, e.g. http://localhost:9091
+    #[cfg(feature = "metrics")]
+    #[clap(long)]
+    pub metrics_url: Option<String>,
+
+    /// How often to push metrics to the Pushgateway, in seconds
+    #[cfg(feature = "metrics")]
+    #[clap(long, default_value_t = 15)]
+    pub metrics_interval: u64,
+
+    /// Proactively queue the next song this many seconds before the current one ends, instead of
+    /// only reacting once the player goes idle
+    ///
+    /// This removes the brief gap/stutter that can occur when rshuffle only appends a song after
+    /// the current one has already finished. Disabled by default.
+    #[clap(long, value_parser = parse_preload_secs)]
+    pub preload_secs: Option<f64>,
 }
 
+/// Parses `--preload-secs`, rejecting negative values since they would underflow the `Duration`
+/// computed from them.
+fn parse_preload_secs(s: &str) -> Result<f64, String> {
+    let secs: f64 = s.parse().map_err(|_| format!("\"{s}\" is not a valid number"))?;
+
+    if secs < 0.0 {
+        return Err("must not be negative".to_string());
+    }
+
+    Ok(secs)
+}
+
+/// How many recently-queued songs' `(artist, album)` pairs to penalize candidates against when
+/// `--smart-shuffle` is enabled.
+const SMART_SHUFFLE_WINDOW: usize = 8;
+
+/// The weight multiplier applied for each of artist/album appearing in the recent window.
+const SMART_SHUFFLE_PENALTY: f64 = 0.1;
+
 struct AppContext {
     pub uri: String,
     pub num_buffer: u32,
     pub state: Option<AppState>,
     pub rng: ThreadRng,
-    pub filters: Vec<Filter>,
-    // we want inverted filters to be separate because they are applied after the normal filters
-    pub inverted_filters: Vec<Filter>,
+    pub filters: Vec<FilterExpr>,
+    /// The last-observed position of the current song, used to detect when MPD switches to an
+    /// earlier, still-queued song on its own (a backward skip) so we can keep the history cursor
+    /// in sync instead of losing track of where playback actually is.
+    pub last_position: Option<usize>,
+    pub smart_shuffle: bool,
+    /// Ring buffer of `(artist, album)` pairs for the last `SMART_SHUFFLE_WINDOW` songs queued,
+    /// used to penalize candidates that would cluster with what's already playing.
+    pub recent: VecDeque<(Option<String>, Option<String>)>,
+    #[cfg(feature = "metrics")]
+    pub metrics: Option<Arc<Metrics>>,
+    /// Seconds before the end of the current song at which to proactively queue the next one. See
+    /// [`Cli::preload_secs`].
+    pub preload_secs: Option<f64>,
 }
 
 #[tokio::main]
@@ -113,21 +187,28 @@ async fn main() -> Result<()> {
 
     let rng = thread_rng();
 
-    let mut filters = Vec::<Filter>::new();
+    let mut filters = Vec::<FilterExpr>::new();
 
     for filter in cli.filter {
         match filter.parse() {
             Ok(f) => filters.push(f),
             Err(err) => {
-                error!("failed to parse filter \"{filter}\": {err:?}");
+                error!("failed to parse filter \"{filter}\": {err}");
             }
         }
     }
 
     trace!("filters: {filters:?}");
 
-    let (inverted_filters, filters) = filters.into_iter().partition(Filter::is_inverted);
-    trace!("inverted filters: {inverted_filters:?}");
+    #[cfg(feature = "metrics")]
+    let metrics = match cli.metrics_url {
+        Some(url) => {
+            let metrics = Arc::new(Metrics::new(url)?);
+            metrics.spawn_pusher(Duration::from_secs(cli.metrics_interval));
+            Some(metrics)
+        }
+        None => None,
+    };
 
     let mut ctx = AppContext {
         uri,
@@ -139,12 +220,23 @@ async fn main() -> Result<()> {
         },
         rng,
         filters,
-        inverted_filters,
+        last_position: None,
+        smart_shuffle: cli.smart_shuffle,
+        recent: VecDeque::with_capacity(SMART_SHUFFLE_WINDOW),
+        #[cfg(feature = "metrics")]
+        metrics,
+        preload_secs: cli.preload_secs,
     };
 
     while attempts < 3 {
         if let Err(err) = event_loop(&mut ctx).await {
             error!("error in event loop: {}", err);
+
+            #[cfg(feature = "metrics")]
+            if let Some(metrics) = &ctx.metrics {
+                metrics.reconnect_attempts_total.inc();
+            }
+
             if last_attempt_at.elapsed() > ATTEMPT_INTERVAL {
                 debug!("attempt interval elapsed, resetting attempt counter");
                 attempts = 0;
@@ -181,31 +273,87 @@ async fn event_loop(ctx: &mut AppContext) -> Result<()> {
         let status = client.command(cmd::Status).await?;
         trace!("status: {:?}", status);
 
-        let active = is_active(ctx, &status);
-        trace!("activity status: {:?}", active);
-
-        match active {
-            ActivityStatus::NotActive => {
-                debug!("not active, doing nothing");
+        let current_position = status.current_song.as_ref().map(|(pos, _)| pos.0);
+        // current_song going away entirely is also exactly how an ordinary queue underrun looks
+        // (is_active's job to refill), so it can't be used to detect a backward skip - with
+        // --num-buffer 0 that transition happens after every single song. The only unambiguous
+        // backward-skip signal is MPD reporting a position that still exists but has regressed:
+        // that means it switched to an earlier, still-queued song on its own, so rshuffle doesn't
+        // need to touch the queue at all, just keep its own history cursor in sync with where
+        // playback actually is.
+        let went_backward = ctx
+            .last_position
+            .zip(current_position)
+            .is_some_and(|(last, current)| current < last);
+        ctx.last_position = current_position;
+
+        if went_backward {
+            if let Some(prev) = ctx.state.as_mut().and_then(AppState::step_back) {
+                debug!("position moved backward to an already-queued song (\"{prev}\"), syncing history cursor");
             }
-            ActivityStatus::Active(n, play_first) => {
-                trace!("active, adding {} songs to queue", n);
-                let switch_to = if play_first {
-                    Some(status.playlist_length)
-                } else {
-                    None
-                };
-                queue_next(&mut client, ctx, switch_to).await?;
+        }
+
+        let history_exhausted = ctx
+            .state
+            .as_ref()
+            .map_or(true, AppState::history_exhausted);
 
-                for _ in 0..n - 1 {
-                    queue_next(&mut client, ctx, None).await?;
+        if !went_backward || history_exhausted {
+            let active = is_active(ctx, &status);
+            trace!("activity status: {:?}", active);
+
+            match active {
+                ActivityStatus::NotActive => {
+                    debug!("not active, doing nothing");
+                }
+                ActivityStatus::Active(n, play_first) => {
+                    trace!("active, adding {} songs to queue", n);
+                    let switch_to = if play_first {
+                        Some(status.playlist_length)
+                    } else {
+                        None
+                    };
+                    queue_next(&mut client, ctx, switch_to).await?;
+
+                    for _ in 0..n - 1 {
+                        queue_next(&mut client, ctx, None).await?;
+                    }
                 }
             }
         }
 
         trace!("watching Queue and Player subsystems");
+        let deadline = preload_deadline(ctx, &status);
+        let mut preloaded = false;
+
         loop {
-            let Some(event) = events.next().await else {
+            let event = loop {
+                match deadline {
+                    Some(deadline) => {
+                        tokio::select! {
+                            () = tokio::time::sleep_until(deadline), if !preloaded => {
+                                preloaded = true;
+                                debug!("preload threshold reached, checking whether to queue ahead");
+
+                                // don't gate this on `is_active`: it treats "a song is already
+                                // queued and num_buffer == 0" as nothing to do, but that's exactly
+                                // the steady-state `--preload-secs` is meant to act in. Only skip
+                                // if a next song is already queued, so we don't queue duplicates.
+                                if status.next_song.is_none() {
+                                    trace!("preloading a song ahead of the transition");
+                                    queue_next(&mut client, ctx, None).await?;
+                                }
+
+                                continue;
+                            }
+                            event = events.next() => break event,
+                        }
+                    }
+                    None => break events.next().await,
+                }
+            };
+
+            let Some(event) = event else {
                 bail!("connection closed gracefully");
             };
 
@@ -224,6 +372,22 @@ async fn event_loop(ctx: &mut AppContext) -> Result<()> {
     }
 }
 
+/// Compute when we should proactively run [`queue_next`] so the next song is ready before the
+/// current one ends, based on `--preload-secs` and the player's `elapsed`/`duration`.
+///
+/// Returns `None` if preloading is disabled or the player isn't reporting progress (e.g. nothing
+/// is playing).
+fn preload_deadline(ctx: &AppContext, status: &res::Status) -> Option<tokio::time::Instant> {
+    let preload_secs = ctx.preload_secs?;
+    let elapsed = status.elapsed?;
+    let duration = status.duration?;
+
+    let remaining = duration.checked_sub(elapsed)?;
+    let sleep_for = remaining.saturating_sub(Duration::from_secs_f64(preload_secs));
+
+    Some(tokio::time::Instant::now() + sleep_for)
+}
+
 #[derive(Debug)]
 enum ActivityStatus {
     /// We do not need to add anymore songs to the queue
@@ -310,43 +474,42 @@ async fn queue_next(
         state,
         rng,
         filters,
-        inverted_filters,
+        last_position: _,
+        smart_shuffle,
+        recent,
+        #[cfg(feature = "metrics")]
+        metrics,
+        preload_secs: _,
     } = ctx;
 
-    // listall only returns the song paths which isn't enough information if we want to filter
-    let mut songs = client.command(cmd::ListAllIn::root()).await?;
-    trace!("received {} songs from MPD", songs.len());
+    let mut songs = match filter::compile_mpd_expr(filters) {
+        Some(expr) => {
+            debug!("trying server-side filter expression: {expr}");
 
-    if songs.is_empty() {
-        return Err(eyre!("no songs in library"));
-    }
+            match client.command(cmd::Find::new(MpdFilter::new(expr))).await {
+                Ok(songs) => {
+                    trace!("received {} songs from MPD via server-side filter", songs.len());
 
-    if !filters.is_empty() {
-        songs = songs
-            .into_iter()
-            .filter(|song| filters.iter().any(|filter| filter.matches(song)))
-            .collect::<Vec<_>>();
-        debug!("{} songs left after filtering", songs.len());
+                    if songs.is_empty() {
+                        // this is an error because we haven't filtered out already played tracks
+                        // which means the filters match nothing and probably never will
+                        return Err(eyre!("no songs left after filtering"));
+                    }
 
-        if songs.is_empty() {
-            // this is an error because we haven't filtered out already played tracks which means
-            // the filters match nothing and probably never will
-            return Err(eyre!("no songs left after filtering"));
+                    songs
+                }
+                Err(err) => {
+                    warn!("server rejected filter expression, falling back to full scan: {err:?}");
+                    fetch_and_filter(client, filters).await?
+                }
+            }
         }
-    }
-
-    if !inverted_filters.is_empty() {
-        songs = songs
-            .into_iter()
-            .filter(|song| inverted_filters.iter().all(|filter| !filter.matches(song)))
-            .collect::<Vec<_>>();
-        debug!("{} songs left after inverted filtering", songs.len());
+        None => fetch_and_filter(client, filters).await?,
+    };
 
-        if songs.is_empty() {
-            // this is an error because we haven't filtered out already played tracks which means
-            // the filters match nothing and probably never will
-            return Err(eyre!("no songs left after inverted filtering"));
-        }
+    #[cfg(feature = "metrics")]
+    if let Some(metrics) = &metrics {
+        metrics.songs_in_pool.set(songs.len() as i64);
     }
 
     if let Some(state) = state {
@@ -362,21 +525,50 @@ async fn queue_next(
             .collect::<Vec<_>>();
         info!("{} songs left to play", songs.len());
 
+        #[cfg(feature = "metrics")]
+        if let Some(metrics) = &metrics {
+            metrics.songs_remaining.set(songs.len() as i64);
+        }
+
         if songs.is_empty() {
             warn!("no songs left to play, resetting");
-            state.clear();
+            state.clear().await?;
             return queue_next(client, ctx, switch_to).await;
         }
     }
 
-    let next = songs
-        .choose(rng)
-        .ok_or_else(|| eyre!("no songs to choose from"))?;
+    let next = if *smart_shuffle {
+        choose_smart(&songs, recent, rng).ok_or_else(|| eyre!("no songs to choose from"))?
+    } else {
+        songs
+            .choose(rng)
+            .ok_or_else(|| eyre!("no songs to choose from"))?
+    };
 
     info!("playing {}", next.url);
 
+    if recent.len() >= SMART_SHUFFLE_WINDOW {
+        recent.pop_front();
+    }
+    recent.push_back((
+        next.artists().first().cloned(),
+        next.album().map(str::to_string),
+    ));
+
     client.command(cmd::Add::uri(&next.url)).await?;
 
+    #[cfg(feature = "metrics")]
+    if let Some(metrics) = &metrics {
+        metrics.songs_queued_total.inc();
+
+        if let Some(artist) = next.artists().first() {
+            metrics
+                .songs_queued_by_artist
+                .with_label_values(&[artist.as_str()])
+                .inc();
+        }
+    }
+
     // status was captured before we added the song and queue is zero-indexed, so we can use the old
     // length as the new position
     if let Some(switch_to) = switch_to {
@@ -387,11 +579,88 @@ async fn queue_next(
     }
 
     if let Some(state) = state {
-        state.mark_as_played(next);
-        state.save().await?;
+        state.mark_as_played(next).await?;
+        state.push_history(next).await?;
 
         trace!("state: {state:?}");
     }
 
     Ok(())
 }
+
+/// Pick a song weighted away from artists/albums that appeared recently, so a true shuffle doesn't
+/// keep stacking the same artist or album back-to-back.
+///
+/// Falls back to a uniform pick if every candidate is penalized (e.g. the remaining pool is
+/// smaller than the recent window).
+fn choose_smart<'s>(
+    songs: &'s [res::Song],
+    recent: &VecDeque<(Option<String>, Option<String>)>,
+    rng: &mut ThreadRng,
+) -> Option<&'s res::Song> {
+    let weights = songs
+        .iter()
+        .map(|song| song_weight(song, recent))
+        .collect::<Vec<_>>();
+
+    if weights.iter().all(|w| *w < 1.0) {
+        trace!("every candidate is penalized, falling back to uniform selection");
+        return songs.choose(rng);
+    }
+
+    songs
+        .choose_weighted(rng, |song| song_weight(song, recent))
+        .ok()
+}
+
+/// Compute the selection weight for `song` given the recently-queued `(artist, album)` pairs.
+///
+/// The base weight is `1.0`, multiplied by `SMART_SHUFFLE_PENALTY` for each of the song's artist
+/// or album appearing in the recent window.
+fn song_weight(song: &res::Song, recent: &VecDeque<(Option<String>, Option<String>)>) -> f64 {
+    let artist = song.artists().first().map(String::as_str);
+    let album = song.album();
+
+    let mut weight = 1.0;
+
+    if artist.is_some() && recent.iter().any(|(a, _)| a.as_deref() == artist) {
+        weight *= SMART_SHUFFLE_PENALTY;
+    }
+
+    if album.is_some() && recent.iter().any(|(_, al)| al.as_deref() == album) {
+        weight *= SMART_SHUFFLE_PENALTY;
+    }
+
+    weight
+}
+
+/// Fall back to listing every song in the library and filtering client-side.
+///
+/// This is used when there are no filters to compile into a native MPD filter expression, or when
+/// the server rejects the compiled expression.
+#[instrument(skip_all)]
+async fn fetch_and_filter(client: &mut Client, filters: &[FilterExpr]) -> Result<Vec<res::Song>> {
+    // listall only returns the song paths which isn't enough information if we want to filter
+    let mut songs = client.command(cmd::ListAllIn::root()).await?;
+    trace!("received {} songs from MPD", songs.len());
+
+    if songs.is_empty() {
+        return Err(eyre!("no songs in library"));
+    }
+
+    if !filters.is_empty() {
+        songs = songs
+            .into_iter()
+            .filter(|song| filters.iter().any(|filter| filter.matches(song)))
+            .collect::<Vec<_>>();
+        debug!("{} songs left after filtering", songs.len());
+
+        if songs.is_empty() {
+            // this is an error because we haven't filtered out already played tracks which means
+            // the filters match nothing and probably never will
+            return Err(eyre!("no songs left after filtering"));
+        }
+    }
+
+    Ok(songs)
+}
@@ -0,0 +1,125 @@
+//! Optional Prometheus Pushgateway metrics, enabled via the `metrics` cargo feature.
+//!
+//! This lets people running rshuffle as a long-lived daemon observe library coverage and playback
+//! rate without scraping MPD directly.
+
+use std::{sync::Arc, time::Duration};
+
+use color_eyre::eyre::{Context, Result};
+use prometheus::{Encoder, IntCounter, IntCounterVec, IntGauge, Opts, Registry, TextEncoder};
+
+pub struct Metrics {
+    registry: Registry,
+    pushgateway_url: String,
+
+    /// Songs remaining in the filtered pool, i.e. after applying `--filter`.
+    pub songs_in_pool: IntGauge,
+    /// Songs remaining before the `already_played` set resets.
+    pub songs_remaining: IntGauge,
+    /// Total songs queued since startup.
+    pub songs_queued_total: IntCounter,
+    /// Total reconnect attempts since startup.
+    pub reconnect_attempts_total: IntCounter,
+    /// Total songs queued since startup, labeled by the selected song's artist.
+    pub songs_queued_by_artist: IntCounterVec,
+}
+
+impl Metrics {
+    pub fn new(pushgateway_url: String) -> Result<Self> {
+        let registry = Registry::new();
+
+        let songs_in_pool = IntGauge::new("rshuffle_songs_in_pool", "songs in the filtered pool")
+            .wrap_err("failed to create songs_in_pool gauge")?;
+        let songs_remaining = IntGauge::new(
+            "rshuffle_songs_remaining",
+            "songs remaining before the already_played set resets",
+        )
+        .wrap_err("failed to create songs_remaining gauge")?;
+        let songs_queued_total = IntCounter::new(
+            "rshuffle_songs_queued_total",
+            "total songs queued since startup",
+        )
+        .wrap_err("failed to create songs_queued_total counter")?;
+        let reconnect_attempts_total = IntCounter::new(
+            "rshuffle_reconnect_attempts_total",
+            "total reconnect attempts since startup",
+        )
+        .wrap_err("failed to create reconnect_attempts_total counter")?;
+        let songs_queued_by_artist = IntCounterVec::new(
+            Opts::new(
+                "rshuffle_songs_queued_by_artist_total",
+                "total songs queued since startup, labeled by artist",
+            ),
+            &["artist"],
+        )
+        .wrap_err("failed to create songs_queued_by_artist counter")?;
+
+        registry
+            .register(Box::new(songs_in_pool.clone()))
+            .wrap_err("failed to register songs_in_pool gauge")?;
+        registry
+            .register(Box::new(songs_remaining.clone()))
+            .wrap_err("failed to register songs_remaining gauge")?;
+        registry
+            .register(Box::new(songs_queued_total.clone()))
+            .wrap_err("failed to register songs_queued_total counter")?;
+        registry
+            .register(Box::new(reconnect_attempts_total.clone()))
+            .wrap_err("failed to register reconnect_attempts_total counter")?;
+        registry
+            .register(Box::new(songs_queued_by_artist.clone()))
+            .wrap_err("failed to register songs_queued_by_artist counter")?;
+
+        Ok(Self {
+            registry,
+            pushgateway_url,
+            songs_in_pool,
+            songs_remaining,
+            songs_queued_total,
+            reconnect_attempts_total,
+            songs_queued_by_artist,
+        })
+    }
+
+    /// Spawn a background task which pushes the current metrics to the Pushgateway every
+    /// `interval`, logging (but not failing on) push errors.
+    pub fn spawn_pusher(self: &Arc<Self>, interval: Duration) {
+        let metrics = Arc::clone(self);
+
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+
+            loop {
+                ticker.tick().await;
+
+                if let Err(err) = metrics.push().await {
+                    error!("failed to push metrics: {err:?}");
+                }
+            }
+        });
+    }
+
+    async fn push(&self) -> Result<()> {
+        let encoder = TextEncoder::new();
+        let metric_families = self.registry.gather();
+
+        let mut buf = Vec::new();
+        encoder
+            .encode(&metric_families, &mut buf)
+            .wrap_err("failed to encode metrics")?;
+
+        trace!("pushing {} bytes of metrics to pushgateway", buf.len());
+
+        reqwest::Client::new()
+            .post(format!("{}/metrics/job/rshuffle", self.pushgateway_url))
+            .header("Content-Type", encoder.format_type())
+            .body(buf)
+            .send()
+            .await
+            .wrap_err("failed to push metrics to pushgateway")?
+            .error_for_status()
+            .wrap_err("pushgateway returned an error status")?;
+
+        Ok(())
+    }
+}
@@ -10,6 +10,14 @@ pub struct AppState {
     #[serde(skip)]
     persist: bool,
     already_played: HashSet<String>,
+    /// Songs queued so far, in the order they were queued, so that skipping backward can replay a
+    /// previous selection instead of picking a fresh random one.
+    #[serde(default)]
+    history: Vec<String>,
+    /// How many songs back from the live end of `history` we currently are. `0` means we're at
+    /// the live end and normal random selection should be used.
+    #[serde(default)]
+    history_cursor: usize,
 }
 
 impl Debug for AppState {
@@ -19,6 +27,8 @@ impl Debug for AppState {
         f.debug_struct("AppState")
             .field("persist", &self.persist)
             .field("already_played", &self.already_played.len())
+            .field("history", &self.history.len())
+            .field("history_cursor", &self.history_cursor)
             .finish()
     }
 }
@@ -89,9 +99,114 @@ impl AppState {
         self.save().await
     }
 
+    /// Record that `song` was just queued as a brand-new selection, resetting the history cursor
+    /// back to the live end.
+    pub async fn push_history(&mut self, song: &Song) -> Result<()> {
+        self.history.push(song.url.clone());
+        self.history_cursor = 0;
+
+        self.save().await
+    }
+
+    /// Move the history cursor one step further back from the live end and return the song at
+    /// that point, or `None` if there is no history or we're already at the oldest recorded song.
+    ///
+    /// On failure the cursor is reset to the live end (i.e. [`history_exhausted`] becomes `true`),
+    /// so a caller that keeps stepping back past the oldest entry falls back to fresh random
+    /// selection instead of getting stuck permanently thinking there's still history to replay.
+    ///
+    /// [`history_exhausted`]: Self::history_exhausted
+    pub fn step_back(&mut self) -> Option<String> {
+        if self.history.is_empty() {
+            self.history_cursor = 0;
+            return None;
+        }
+
+        let next_cursor = self.history_cursor + 1;
+        if next_cursor > self.history.len() - 1 {
+            self.history_cursor = 0;
+            return None;
+        }
+
+        self.history_cursor = next_cursor;
+        let index = self.history.len() - 1 - self.history_cursor;
+
+        Some(self.history[index].clone())
+    }
+
+    /// Whether the history cursor has returned to the live end, meaning normal random selection
+    /// should resume.
+    pub fn history_exhausted(&self) -> bool {
+        self.history_cursor == 0
+    }
+
     pub async fn clear(&mut self) -> Result<()> {
         self.already_played.clear();
 
         self.save().await
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Builds a state as if `push_history` had been called once per url in `urls`, in order,
+    /// without needing a real `mpd_client::responses::Song` to do it.
+    fn played(urls: &[&str]) -> AppState {
+        let mut state = AppState::default();
+
+        for url in urls {
+            state.history.push((*url).to_string());
+            state.history_cursor = 0;
+        }
+
+        state
+    }
+
+    #[test]
+    fn fresh_history_is_exhausted() {
+        let state = played(&["a", "b", "c"]);
+        assert!(state.history_exhausted());
+    }
+
+    #[test]
+    fn step_back_walks_through_history_and_re_exhausts_at_the_oldest_entry() {
+        let mut state = played(&["a", "b", "c"]);
+
+        assert_eq!(state.step_back().as_deref(), Some("b"));
+        assert!(!state.history_exhausted());
+
+        assert_eq!(state.step_back().as_deref(), Some("a"));
+        assert!(!state.history_exhausted());
+
+        // no further history to replay: this must fail *and* flag us as exhausted again, or
+        // normal random selection can never resume (this previously got stuck permanently)
+        assert_eq!(state.step_back(), None);
+        assert!(state.history_exhausted());
+
+        // hitting the floor repeatedly stays well-behaved
+        assert_eq!(state.step_back(), None);
+        assert!(state.history_exhausted());
+    }
+
+    #[test]
+    fn step_back_on_empty_history_is_a_noop() {
+        let mut state = AppState::default();
+
+        assert_eq!(state.step_back(), None);
+        assert!(state.history_exhausted());
+    }
+
+    #[test]
+    fn playing_a_new_song_after_stepping_back_resets_to_the_live_end() {
+        let mut state = played(&["a", "b"]);
+        state.step_back();
+        assert!(!state.history_exhausted());
+
+        state.history.push("c".to_string());
+        state.history_cursor = 0;
+
+        assert!(state.history_exhausted());
+    }
+}